@@ -35,9 +35,10 @@ impl Bigint {
 }
 
 impl Mask for Bigint {
-    /// bit returns 1 if the bit at position n is 1, 0 otherwise.
+    /// bit returns 1 if the bit at position n is 1, 0 otherwise. Valid
+    /// positions are `0..sz`; anything else is treated as unset.
     fn bit(&self, n: usize) -> bool {
-        if n > self.sz {
+        if n >= self.sz {
             return false;
         }
         let segment: usize = self.segments.len() - (n / 64) - 1;
@@ -46,9 +47,10 @@ impl Mask for Bigint {
         self.segments[segment] & (1 << pos) != 0
     }
 
-    /// set_bit sets the bit at position n to 1
+    /// set_bit sets the bit at position n to 1. Positions outside `0..sz`
+    /// are silently dropped, mirroring `bit`.
     fn set_bit(&mut self, n: usize) {
-        if n > self.sz {
+        if n >= self.sz {
             return;
         }
         let i: usize = self.segments.len() - (n / 64) - 1;
@@ -61,6 +63,14 @@ impl Mask for Bigint {
         if n == 0 {
             return;
         }
+        // Shifting by the window size or more moves every existing bit
+        // outside the valid `0..sz` range, so the window is simply empty.
+        if n >= self.sz {
+            for seg in self.segments.iter_mut() {
+                *seg = 0;
+            }
+            return;
+        }
         let len = self.segments.len();
         let pos = n % 64;
         let seg = n / 64;
@@ -117,7 +127,9 @@ mod test {
         let mut bi = Bigint::new(4);
         bi.set_bit(3);
         bi.shl(1);
-        assert!(bi.bit(4));
+        // position 3 shifted to position 4, which is outside the window
+        // (valid positions are 0..sz) and is no longer observable.
+        assert!(!bi.bit(4));
         bi.shl(1);
         assert!(!bi.bit(4));
     }
@@ -140,9 +152,61 @@ mod test {
     #[test]
     fn bits_outside_range() {
         let mut bi = Bigint::new(4);
+        // sz == 4 is itself out of range (valid positions are 0..4), not
+        // just positions past it.
+        bi.set_bit(4);
         bi.set_bit(5);
-        for i in 0..5 {
+        for i in 0..6 {
             assert!(!bi.bit(i));
         }
     }
 }
+
+#[cfg(test)]
+mod proptests {
+    use super::*;
+    use crate::dequeue::Dequeue;
+    use proptest::collection::vec;
+    use proptest::prelude::*;
+
+    #[derive(Debug, Clone, Copy)]
+    enum Op {
+        SetBit(usize),
+        Shl(usize),
+    }
+
+    fn op_strategy(sz: usize) -> impl Strategy<Value = Op> {
+        prop_oneof![
+            (0..sz + 2).prop_map(Op::SetBit),
+            (0..sz + 2).prop_map(Op::Shl),
+        ]
+    }
+
+    proptest! {
+        /// Bigint and Dequeue must agree on every `bit` query for any
+        /// sequence of `set_bit`/`shl` operations, across the full valid
+        /// range and its immediate out-of-range neighbors.
+        #[test]
+        fn bigint_and_dequeue_agree(ops in vec(op_strategy(32), 0..50)) {
+            let mut bigint = Bigint::new(32);
+            let mut dequeue = Dequeue::new(32);
+
+            for op in ops {
+                match op {
+                    Op::SetBit(n) => {
+                        bigint.set_bit(n);
+                        dequeue.set_bit(n);
+                    }
+                    Op::Shl(n) => {
+                        bigint.shl(n);
+                        dequeue.shl(n);
+                    }
+                }
+            }
+
+            for n in 0..34 {
+                prop_assert_eq!(bigint.bit(n), dequeue.bit(n));
+            }
+        }
+    }
+}
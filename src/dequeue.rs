@@ -15,26 +15,39 @@ impl Dequeue {
 }
 
 impl Mask for Dequeue {
+    /// bit returns true if the bit at position n is set. Valid positions
+    /// are `0..size`; anything else is treated as unset.
     fn bit(&self, n: usize) -> bool {
-        if n > self.size {
+        if n >= self.size {
             return false;
         }
         self.vec[self.size - n - 1] == 1
     }
+
+    /// set_bit sets the bit at position n to 1. Positions outside `0..size`
+    /// are silently dropped, mirroring `bit`.
     fn set_bit(&mut self, n: usize) {
-        if n > self.size {
+        if n >= self.size {
             return;
         }
-        println!("{}, {}", n, self.size);
         self.vec[self.size - n - 1] = 1;
     }
+
     fn shl(&mut self, n: usize) {
-        if n > self.size {
-            self.vec.clear();
+        if n == 0 {
+            return;
+        }
+        // Shifting by the window size or more moves every existing bit
+        // outside the valid `0..size` range, so the window is simply empty.
+        if n >= self.size {
+            for b in self.vec.iter_mut() {
+                *b = 0;
+            }
             return;
         }
         for _ in 0..n {
             self.vec.push_back(0);
+            self.vec.pop_front();
         }
     }
 }